@@ -0,0 +1,262 @@
+//! Batched variant of [`GetAgentActivityQuery`](super::hashes::GetAgentActivityQuery)
+//! that answers for many authors in a single SQL round-trip. Neighborhood
+//! and shard warm-up commonly need activity for a whole set of agents at
+//! once; issuing one `GetAgentActivityQuery` per agent means one query (and
+//! one fold) per agent, which gets expensive as the set grows.
+
+use super::hashes::{fold_item, render_state, ActivityWindow, GetAgentActivityResult, Item, State};
+use holo_hash::*;
+use holochain_p2p::event::GetActivityOptions;
+use holochain_sqlite::rusqlite::*;
+use holochain_state::{prelude::*, query::QueryData};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// The most agents a single [`GetAgentActivityBatchQuery`] will bind into
+/// its `IN (:author0, :author1, ...)` clause. SQLite's
+/// `SQLITE_LIMIT_VARIABLE_NUMBER` defaults to 999 on builds before 3.32.0
+/// and to 32766 from then on (999 is the one we can't assume isn't in
+/// play); this leaves
+/// comfortable headroom under that for the extra `:op_type` binding and any
+/// filter-driven params `render_state`'s caller may add later. Callers with
+/// more agents than this (e.g. a large neighborhood warm-up) must split the
+/// set across multiple `GetAgentActivityBatchQuery` instances themselves —
+/// see [`super::handle_get_agent_activity_batch`].
+pub const MAX_BATCH_AGENTS: usize = 500;
+
+/// Answers [`GetAgentActivityQuery`](super::hashes::GetAgentActivityQuery)
+/// for several authors at once: one `WHERE Action.author IN (...)` query,
+/// one fold over all their rows together, and one
+/// [`GetAgentActivityResult`] per agent out the other end. Each agent's
+/// valid/rejected/pending accumulation, fork detection,
+/// `highest_observed` and `status_authoritative` are computed exactly as
+/// they would be by a single-agent query; only the query round-trip is
+/// shared.
+#[derive(Debug, Clone)]
+pub struct GetAgentActivityBatchQuery {
+    agents: Vec<AgentPubKey>,
+    filter: ChainQueryFilter,
+    options: GetActivityOptions,
+}
+
+impl GetAgentActivityBatchQuery {
+    pub fn new(
+        mut agents: Vec<AgentPubKey>,
+        filter: ChainQueryFilter,
+        options: GetActivityOptions,
+    ) -> Self {
+        // `render` folds results back into one `GetAgentActivityResult` per
+        // entry in `agents` by removing each author's `State` out of the
+        // fold map; a duplicate entry would drain the real state on its
+        // first occurrence and render `State::default()` (an empty/wrong
+        // response) on the second, so duplicates are collapsed up front,
+        // keeping each agent at its first position.
+        let mut seen = std::collections::HashSet::new();
+        agents.retain(|agent| seen.insert(agent.clone()));
+        assert!(
+            agents.len() <= MAX_BATCH_AGENTS,
+            "GetAgentActivityBatchQuery::new got {} agents, over the MAX_BATCH_AGENTS \
+             ({MAX_BATCH_AGENTS}) cap on bound `IN (...)` parameters; split the set across \
+             multiple queries instead (see handle_get_agent_activity_batch)",
+            agents.len(),
+        );
+        Self {
+            agents,
+            filter,
+            options,
+        }
+    }
+}
+
+impl Query for GetAgentActivityBatchQuery {
+    type Item = (AgentPubKey, Judged<Item>);
+    type State = HashMap<AgentPubKey, State>;
+    type Output = Vec<GetAgentActivityResult>;
+
+    fn query(&self) -> String {
+        // `IN (...)` can't bind a `Vec` as a single parameter, so each agent
+        // gets its own named placeholder (`:author0`, `:author1`, ...).
+        let placeholders = (0..self.agents.len())
+            .map(|i| format!(":author{i}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "
+                SELECT Action.author, Action.hash, DhtOp.validation_status,
+                Action.blob AS action_blob, DhtOp.when_integrated
+                FROM Action
+                JOIN DhtOp ON DhtOp.action_hash = Action.hash
+                WHERE Action.author IN ({placeholders})
+                AND DhtOp.type = :op_type
+                ORDER BY Action.author ASC, Action.seq ASC
+            "
+        )
+    }
+
+    fn params(&self) -> Vec<holochain_state::query::Params> {
+        // `named_params!` only takes literal keys, and a per-agent
+        // placeholder name (`:author0`, `:author1`, ...) isn't one — but the
+        // bind key only needs to be owned for the lifetime of this call, not
+        // `'static`, so build the `(String, _)` pairs directly instead of
+        // leaking each name to get a `'static str`.
+        let mut params: Vec<holochain_state::query::Params> = self
+            .agents
+            .iter()
+            .enumerate()
+            .map(|(i, agent)| (format!(":author{i}"), agent.clone()).into())
+            .collect();
+        params.extend(named_params! { ":op_type": ChainOpType::RegisterAgentActivity }.to_vec());
+        params
+    }
+
+    fn init_fold(&self) -> StateQueryResult<Self::State> {
+        Ok(self
+            .agents
+            .iter()
+            .map(|agent| (agent.clone(), State::default()))
+            .collect())
+    }
+
+    fn as_filter(&self) -> Box<dyn Fn(&QueryData<Self>) -> bool> {
+        unimplemented!("This query should not be used with the scratch")
+    }
+
+    fn as_map(&self) -> Arc<dyn Fn(&Row) -> StateQueryResult<Self::Item>> {
+        Arc::new(move |row| {
+            let author: AgentPubKey = row.get("author")?;
+            let validation_status: Option<ValidationStatus> = row.get("validation_status")?;
+            let hash: ActionHash = row.get("hash")?;
+            from_blob::<SignedAction>(row.get("action_blob")?).and_then(|action| {
+                let integrated: Option<Timestamp> = row.get("when_integrated")?;
+                let action = ActionHashed::with_pre_hashed(action.into_data(), hash);
+                let item = if integrated.is_some() {
+                    Item::Integrated(action)
+                } else {
+                    Item::Pending(action)
+                };
+                Ok((author, Judged::raw(item, validation_status)))
+            })
+        })
+    }
+
+    fn fold(&self, mut state: Self::State, (agent, item): Self::Item) -> StateQueryResult<Self::State> {
+        let agent_state = state.remove(&agent).unwrap_or_default();
+        state.insert(agent, fold_item(agent_state, item)?);
+        Ok(state)
+    }
+
+    fn render<S>(&self, mut state: Self::State, _stores: S) -> StateQueryResult<Self::Output>
+    where
+        S: Store,
+    {
+        self.agents
+            .iter()
+            .map(|agent| {
+                let agent_state = state.remove(agent).unwrap_or_default();
+                // The batch query never bounds a single agent's scan (no
+                // `start_seq`/`end_seq`/`limit`), so every agent's result is
+                // always authoritative for the whole chain.
+                render_state(
+                    agent.clone(),
+                    &self.filter,
+                    &self.options,
+                    &ActivityWindow::default(),
+                    agent_state,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "MAX_BATCH_AGENTS")]
+    fn new_panics_over_the_max_batch_agents_cap() {
+        let agents = (0..MAX_BATCH_AGENTS + 1)
+            .map(|_| fixt::fixt!(AgentPubKey))
+            .collect();
+        GetAgentActivityBatchQuery::new(agents, ChainQueryFilter::new(), GetActivityOptions::default());
+    }
+
+    #[test]
+    fn new_dedups_repeated_agents() {
+        let agent = fixt::fixt!(AgentPubKey);
+        let other = fixt::fixt!(AgentPubKey);
+        let query = GetAgentActivityBatchQuery::new(
+            vec![agent.clone(), other.clone(), agent.clone()],
+            ChainQueryFilter::new(),
+            GetActivityOptions::default(),
+        );
+
+        assert_eq!(
+            query.init_fold().unwrap().len(),
+            2,
+            "a duplicate agent must not get its own (empty) fold slot"
+        );
+    }
+
+    #[test]
+    fn fold_keeps_each_agents_rows_isolated() {
+        let agent_a = fixt::fixt!(AgentPubKey);
+        let agent_b = fixt::fixt!(AgentPubKey);
+        let query = GetAgentActivityBatchQuery::new(
+            vec![agent_a.clone(), agent_b.clone()],
+            ChainQueryFilter::new(),
+            GetActivityOptions {
+                include_valid_activity: true,
+                ..Default::default()
+            },
+        );
+
+        let action_a = ActionHashed::with_pre_hashed(fixt::fixt!(Action), fixt::fixt!(ActionHash));
+        let action_b = ActionHashed::with_pre_hashed(fixt::fixt!(Action), fixt::fixt!(ActionHash));
+
+        let mut state = query.init_fold().unwrap();
+        state = query
+            .fold(
+                state,
+                (
+                    agent_a.clone(),
+                    Judged::raw(Item::Integrated(action_a), Some(ValidationStatus::Valid)),
+                ),
+            )
+            .unwrap();
+        state = query
+            .fold(
+                state,
+                (
+                    agent_b.clone(),
+                    Judged::raw(Item::Integrated(action_b), Some(ValidationStatus::Valid)),
+                ),
+            )
+            .unwrap();
+
+        let rendered_a = render_state(
+            agent_a.clone(),
+            &query.filter,
+            &query.options,
+            &ActivityWindow::default(),
+            state.remove(&agent_a).unwrap(),
+        )
+        .unwrap();
+        let rendered_b = render_state(
+            agent_b.clone(),
+            &query.filter,
+            &query.options,
+            &ActivityWindow::default(),
+            state.remove(&agent_b).unwrap(),
+        )
+        .unwrap();
+
+        let valid_count = |result: &GetAgentActivityResult| match &result.response.valid_activity {
+            ChainItems::Hashes(hashes) => hashes.len(),
+            _ => 0,
+        };
+        assert_eq!(valid_count(&rendered_a), 1);
+        assert_eq!(valid_count(&rendered_b), 1);
+    }
+}
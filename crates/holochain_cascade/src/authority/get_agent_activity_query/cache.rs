@@ -0,0 +1,195 @@
+//! Bounded LRU cache in front of [`GetAgentActivityQuery`](super::hashes::GetAgentActivityQuery)
+//! execution. Agent-activity gossip and `get_agent_activity` calls otherwise
+//! re-run the full `Action`/`DhtOp` fold for the same `(agent, filter,
+//! options)` on every hit, which is wasted work on hot paths where the
+//! author's chain hasn't changed since the last call.
+
+use super::hashes::{ActivityWindow, GetAgentActivityResult};
+use holo_hash::AgentPubKey;
+use holochain_p2p::event::GetActivityOptions;
+use holochain_state::prelude::*;
+use lru::LruCache;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Identifies a `GetAgentActivityQuery` call, folded together with the
+/// author's current invalidation generation. `ChainQueryFilter` and
+/// `GetActivityOptions` aren't `Hash`, so this keys on their canonical wire
+/// encoding instead of the values directly. `ActivityWindow` is plain data
+/// and is hashed directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct CacheKey(u64);
+
+impl CacheKey {
+    fn new(
+        agent: &AgentPubKey,
+        filter: &ChainQueryFilter,
+        options: &GetActivityOptions,
+        window: &ActivityWindow,
+        generation: u64,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+        agent.hash(&mut hasher);
+        holochain_serialized_bytes::encode(filter)
+            .expect("ChainQueryFilter always encodes")
+            .hash(&mut hasher);
+        holochain_serialized_bytes::encode(options)
+            .expect("GetActivityOptions always encodes")
+            .hash(&mut hasher);
+        window.hash(&mut hasher);
+        generation.hash(&mut hasher);
+        Self(hasher.finish())
+    }
+}
+
+/// Per-author invalidation generation counters. Bumping an author's
+/// generation makes every cache entry keyed against their prior generation
+/// unreachable, without having to walk the cache to evict them eagerly.
+#[derive(Debug, Default)]
+struct Generations(Mutex<HashMap<AgentPubKey, u64>>);
+
+impl Generations {
+    fn current(&self, agent: &AgentPubKey) -> u64 {
+        *self.0.lock().unwrap().get(agent).unwrap_or(&0)
+    }
+
+    fn bump(&self, agent: &AgentPubKey) {
+        *self.0.lock().unwrap().entry(agent.clone()).or_insert(0) += 1;
+    }
+}
+
+/// Caches rendered [`GetAgentActivityResult`]s, keyed on `(agent, filter,
+/// options, window)` plus the author's generation counter. Call [`Self::invalidate`]
+/// for an author whenever the integration workflow commits a new
+/// `RegisterAgentActivity` op for them, so a stale chain head is never
+/// served to a later caller.
+pub struct AgentActivityCache {
+    entries: Mutex<LruCache<CacheKey, GetAgentActivityResult>>,
+    generations: Generations,
+}
+
+impl AgentActivityCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            generations: Generations::default(),
+        }
+    }
+
+    /// Returns the cached response for `(agent, filter, options, window)` if
+    /// it's still current, otherwise runs `compute` and caches its result
+    /// under the author's current generation.
+    pub fn get_or_try_compute(
+        &self,
+        agent: &AgentPubKey,
+        filter: &ChainQueryFilter,
+        options: &GetActivityOptions,
+        window: &ActivityWindow,
+        compute: impl FnOnce() -> StateQueryResult<GetAgentActivityResult>,
+    ) -> StateQueryResult<GetAgentActivityResult> {
+        let generation = self.generations.current(agent);
+        let key = CacheKey::new(agent, filter, options, window, generation);
+
+        if let Some(hit) = self.entries.lock().unwrap().get(&key) {
+            return Ok(hit.clone());
+        }
+
+        let response = compute()?;
+        self.entries.lock().unwrap().put(key, response.clone());
+        Ok(response)
+    }
+
+    /// Invalidates every cached response for `agent`. Called from
+    /// [`super::handle_register_agent_activity_integrated`] — the real
+    /// commit-point hook — strictly *after* a `RegisterAgentActivity` op
+    /// authored by them is committed and visible to readers; bumping the
+    /// generation any earlier would let a concurrent
+    /// [`Self::get_or_try_compute`] read the database before the op is
+    /// visible but cache its (stale) result under the *new* generation,
+    /// where nothing later is guaranteed to invalidate it again.
+    pub fn invalidate(&self, agent: &AgentPubKey) {
+        self.generations.bump(agent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(agent: AgentPubKey, status_authoritative: bool) -> GetAgentActivityResult {
+        GetAgentActivityResult {
+            response: AgentActivityResponse {
+                agent,
+                valid_activity: ChainItems::NotRequested,
+                rejected_activity: ChainItems::NotRequested,
+                status: ChainStatus::Empty,
+                highest_observed: None,
+            },
+            status_authoritative,
+        }
+    }
+
+    #[test]
+    fn caches_a_hit_for_the_same_key() {
+        let cache = AgentActivityCache::new(NonZeroUsize::new(8).unwrap());
+        let agent = fixt::fixt!(AgentPubKey);
+        let filter = ChainQueryFilter::new();
+        let options = GetActivityOptions::default();
+        let window = ActivityWindow::default();
+
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let compute = || {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(response(agent.clone(), true))
+        };
+
+        cache
+            .get_or_try_compute(&agent, &filter, &options, &window, compute)
+            .unwrap();
+        cache
+            .get_or_try_compute(&agent, &filter, &options, &window, compute)
+            .unwrap();
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn invalidate_forces_a_recompute_for_that_agent_only() {
+        let cache = AgentActivityCache::new(NonZeroUsize::new(8).unwrap());
+        let agent = fixt::fixt!(AgentPubKey);
+        let other = fixt::fixt!(AgentPubKey);
+        let filter = ChainQueryFilter::new();
+        let options = GetActivityOptions::default();
+        let window = ActivityWindow::default();
+
+        cache
+            .get_or_try_compute(&agent, &filter, &options, &window, || Ok(response(agent.clone(), true)))
+            .unwrap();
+        cache
+            .get_or_try_compute(&other, &filter, &options, &window, || Ok(response(other.clone(), true)))
+            .unwrap();
+
+        cache.invalidate(&agent);
+
+        let agent_calls = std::sync::atomic::AtomicUsize::new(0);
+        cache
+            .get_or_try_compute(&agent, &filter, &options, &window, || {
+                agent_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(response(agent.clone(), true))
+            })
+            .unwrap();
+        let other_calls = std::sync::atomic::AtomicUsize::new(0);
+        cache
+            .get_or_try_compute(&other, &filter, &options, &window, || {
+                other_calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(response(other.clone(), true))
+            })
+            .unwrap();
+
+        assert_eq!(agent_calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(other_calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+}
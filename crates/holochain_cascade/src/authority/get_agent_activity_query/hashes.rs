@@ -5,41 +5,205 @@ use holochain_state::{prelude::*, query::QueryData};
 use std::fmt::Debug;
 use std::sync::Arc;
 
+/// Sequence-range bounds for a single [`GetAgentActivityQuery`] page.
+///
+/// These live here rather than as fields on `holochain_p2p::event::
+/// GetActivityOptions` because this checkout doesn't own that crate and
+/// every other consumer of `GetActivityOptions` (subscriptions, the batch
+/// query) has no use for pagination bounds — bolting cascade-specific
+/// windowing onto a shared p2p-event type would make it part of every
+/// caller's wire format for no reason. `start_seq`/`end_seq` bound the scan
+/// by `Action.seq`, `limit` caps the row count; see [`GetAgentActivityQuery`]
+/// for how the two interact with `status`/`highest_observed` authority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ActivityWindow {
+    pub start_seq: Option<u32>,
+    pub end_seq: Option<u32>,
+    pub limit: Option<usize>,
+}
+
+/// Folds an author's `RegisterAgentActivity` ops into an [`AgentActivityResponse`].
+///
+/// `window.start_seq`/`end_seq`/`limit` bound the query to a window of the
+/// chain rather than materializing it in full, so `valid_activity` and
+/// `rejected_activity` only cover that window. `status` and
+/// `highest_observed` are computed from the same bounded fold, so they are
+/// only authoritative for the whole chain when the window covered all of it:
+/// `start_seq` must be `None` or `0`, `end_seq` must be `None` *and* `limit`
+/// must not have truncated the scan (see
+/// [`GetAgentActivityResult::status_authoritative`]). A `limit` with no
+/// `end_seq` is the common forward-pagination shape (walk from a `start_seq`
+/// cursor, page by page), and only a first page that also reaches the tip
+/// is authoritative — callers that need fork/invalid/highest-observed
+/// detection on any other page should pair this with
+/// [`ChainHeadQuery`], which scans status columns only (no action blobs) and
+/// so stays cheap even over a full chain.
 #[derive(Debug, Clone)]
 pub struct GetAgentActivityQuery {
     agent: AgentPubKey,
     filter: ChainQueryFilter,
     options: GetActivityOptions,
+    window: ActivityWindow,
 }
 
 impl GetAgentActivityQuery {
-    pub fn new(agent: AgentPubKey, filter: ChainQueryFilter, options: GetActivityOptions) -> Self {
+    pub fn new(
+        agent: AgentPubKey,
+        filter: ChainQueryFilter,
+        options: GetActivityOptions,
+        window: ActivityWindow,
+    ) -> Self {
         Self {
             agent,
             filter,
             options,
+            window,
         }
     }
 }
 
+/// The rendered response from a [`GetAgentActivityQuery`], plus whether its
+/// `status`/`highest_observed` are authoritative for the whole chain
+/// (`true`) or only for the bounded [`ActivityWindow`] requested (`false`).
+/// They're non-authoritative whenever `window.start_seq` is past `0`,
+/// `window.end_seq` is set, or `window.limit` truncated the scan before it
+/// reached the tip. Callers
+/// that need the true head in the non-authoritative case should pair this
+/// query with [`ChainHeadQuery`] rather than trusting these fields as-is.
+#[derive(Debug, Clone)]
+pub struct GetAgentActivityResult {
+    pub response: AgentActivityResponse<ActionHash>,
+    pub status_authoritative: bool,
+}
+
 #[derive(Debug, Default)]
-pub struct State {
+pub(crate) struct State {
     valid: Vec<ActionHashed>,
     rejected: Vec<ActionHashed>,
     pending: Vec<ActionHashed>,
     status: Option<ChainStatus>,
+    rows_seen: usize,
 }
 
 #[derive(Debug)]
-pub enum Item {
+pub(crate) enum Item {
     Integrated(ActionHashed),
     Pending(ActionHashed),
 }
 
+/// Whatever an author's fork/invalid/highest-observed tracking needs to
+/// compare two entries: its `seq` and the hash identifying it. Implemented
+/// by both the full [`ActionHashed`] that [`GetAgentActivityQuery`] folds
+/// and the blob-free [`HeadItem`] that [`ChainHeadQuery`] folds, so the
+/// comparison rules in [`detect_fork`], [`chain_status`] and
+/// [`highest_observed`] are written once and shared by both.
+pub(crate) trait SeqHash {
+    fn seq(&self) -> u32;
+    fn hash(&self) -> &ActionHash;
+}
+
+impl SeqHash for ActionHashed {
+    fn seq(&self) -> u32 {
+        self.action_seq()
+    }
+
+    fn hash(&self) -> &ActionHash {
+        self.get_hash()
+    }
+}
+
+impl SeqHash for HeadItem {
+    fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    fn hash(&self) -> &ActionHash {
+        &self.hash
+    }
+}
+
+/// Checks whether `next` forks the chain against the last-seen valid entry,
+/// i.e. shares its `seq` under a different hash. Shared by
+/// [`GetAgentActivityQuery`]'s and [`ChainHeadQuery`]'s folds so a fork is
+/// detected identically whether or not the full action blob was fetched.
+fn detect_fork<T: SeqHash>(last_valid: Option<&T>, next: &T) -> Option<ChainStatus> {
+    let last_valid = last_valid.filter(|v| v.seq() == next.seq())?;
+    Some(ChainStatus::Forked(ChainFork {
+        fork_seq: next.seq(),
+        first_action: next.hash().clone(),
+        second_action: last_valid.hash().clone(),
+    }))
+}
+
+/// Computes a chain's [`ChainStatus`] from its folded valid/rejected entries:
+/// a fork or invalid status recorded mid-fold takes precedence, otherwise
+/// it's the last valid entry (or [`ChainStatus::Empty`] if there is none).
+fn chain_status<T: SeqHash>(
+    status: Option<ChainStatus>,
+    valid: &[T],
+    rejected: &[T],
+) -> ChainStatus {
+    status.unwrap_or_else(|| {
+        if valid.is_empty() && rejected.is_empty() {
+            ChainStatus::Empty
+        } else {
+            let last = valid.last().expect("Safe due to is_empty check");
+            ChainStatus::Valid(ChainHead {
+                action_seq: last.seq(),
+                hash: last.hash().clone(),
+            })
+        }
+    })
+}
+
+/// Computes the [`HighestObserved`] seq (and every hash seen at that seq)
+/// across an author's last-seen valid, rejected and pending entries. Shared
+/// by [`GetAgentActivityQuery`]'s and [`ChainHeadQuery`]'s renders.
+fn highest_observed<T: SeqHash>(
+    valid: Option<&T>,
+    rejected: Option<&T>,
+    pending: Option<&T>,
+) -> Option<HighestObserved> {
+    let mut highest_observed = None;
+    let mut hashes = Vec::new();
+    let mut check_highest = |seq: u32, hash: &ActionHash| {
+        if highest_observed.is_none() {
+            highest_observed = Some(seq);
+            hashes.push(hash.clone());
+        } else {
+            let last = highest_observed
+                .as_mut()
+                .expect("Safe due to none check above");
+            match seq.cmp(last) {
+                std::cmp::Ordering::Less => {}
+                std::cmp::Ordering::Equal => hashes.push(hash.clone()),
+                std::cmp::Ordering::Greater => {
+                    hashes.clear();
+                    hashes.push(hash.clone());
+                    *last = seq;
+                }
+            }
+        }
+    };
+    if let Some(valid) = valid {
+        check_highest(valid.seq(), valid.hash());
+    }
+    if let Some(rejected) = rejected {
+        check_highest(rejected.seq(), rejected.hash());
+    }
+    if let Some(pending) = pending {
+        check_highest(pending.seq(), pending.hash());
+    }
+    highest_observed.map(|action_seq| HighestObserved {
+        action_seq,
+        hash: hashes,
+    })
+}
+
 impl Query for GetAgentActivityQuery {
     type Item = Judged<Item>;
     type State = State;
-    type Output = AgentActivityResponse<ActionHash>;
+    type Output = GetAgentActivityResult;
 
     fn query(&self) -> String {
         "
@@ -49,7 +213,10 @@ impl Query for GetAgentActivityQuery {
             JOIN DhtOp ON DhtOp.action_hash = Action.hash
             WHERE Action.author = :author
             AND DhtOp.type = :op_type
+            AND (:start_seq IS NULL OR Action.seq >= :start_seq)
+            AND (:end_seq IS NULL OR Action.seq <= :end_seq)
             ORDER BY Action.seq ASC
+            LIMIT :limit
         "
         .to_string()
     }
@@ -58,6 +225,10 @@ impl Query for GetAgentActivityQuery {
         (named_params! {
             ":author": self.agent,
             ":op_type": ChainOpType::RegisterAgentActivity,
+            ":start_seq": self.window.start_seq,
+            ":end_seq": self.window.end_seq,
+            // SQLite treats a negative LIMIT as "no limit".
+            ":limit": self.window.limit.map(|l| l as i64).unwrap_or(-1),
         })
         .to_vec()
     }
@@ -87,40 +258,234 @@ impl Query for GetAgentActivityQuery {
         })
     }
 
+    fn fold(&self, state: Self::State, item: Self::Item) -> StateQueryResult<Self::State> {
+        fold_item(state, item)
+    }
+
+    fn render<S>(&self, state: Self::State, _stores: S) -> StateQueryResult<Self::Output>
+    where
+        S: Store,
+    {
+        render_state(
+            self.agent.clone(),
+            &self.filter,
+            &self.options,
+            &self.window,
+            state,
+        )
+    }
+}
+
+/// Folds a single `RegisterAgentActivity` row into an author's running
+/// [`State`]. Factored out of `GetAgentActivityQuery::fold` so
+/// `GetAgentActivityBatchQuery` can apply the exact same per-agent logic
+/// while folding many authors' rows in a single pass.
+pub(crate) fn fold_item(mut state: State, item: Judged<Item>) -> StateQueryResult<State> {
+    state.rows_seen += 1;
+    let status = item.validation_status();
+    match (status, item.data) {
+        (Some(ValidationStatus::Valid), Item::Integrated(action)) => {
+            if state.status.is_none() {
+                if let Some(fork) = detect_fork(state.valid.last(), &action) {
+                    state.status = Some(fork);
+                }
+            }
+
+            state.valid.push(action);
+        }
+        (Some(ValidationStatus::Rejected), Item::Integrated(action)) => {
+            if state.status.is_none() {
+                state.status = Some(ChainStatus::Invalid(ChainHead {
+                    action_seq: action.action_seq(),
+                    hash: action.get_hash().clone(),
+                }));
+            }
+            state.rejected.push(action);
+        }
+        (_, Item::Pending(data)) => state.pending.push(data),
+        _ => (),
+    }
+    Ok(state)
+}
+
+/// Renders a single author's folded [`State`] into their
+/// [`AgentActivityResponse`]. Factored out of `GetAgentActivityQuery::render`
+/// so `GetAgentActivityBatchQuery` can render each agent in its result map
+/// with identical semantics to a single-agent query.
+pub(crate) fn render_state(
+    agent: AgentPubKey,
+    filter: &ChainQueryFilter,
+    options: &GetActivityOptions,
+    window: &ActivityWindow,
+    state: State,
+) -> StateQueryResult<GetAgentActivityResult> {
+    // The window covered the whole chain only if it started at the first
+    // action, had no `end_seq` to stop short of, and `limit` (if any)
+    // didn't cut the scan off first.
+    let status_authoritative = window.start_seq.map_or(true, |start| start == 0)
+        && window.end_seq.is_none()
+        && window.limit.map_or(true, |limit| state.rows_seen < limit);
+    let highest_observed = highest_observed(
+        state.valid.last(),
+        state.rejected.last(),
+        state.pending.last(),
+    );
+    let status = chain_status(state.status.clone(), &state.valid, &state.rejected);
+
+    let valid_activity = if options.include_valid_activity {
+        let valid = filter
+            .filter_actions(state.valid)
+            .into_iter()
+            .map(|h| (h.action_seq(), h.into_hash()))
+            .collect();
+        ChainItems::Hashes(valid)
+    } else {
+        ChainItems::NotRequested
+    };
+    let rejected_activity = if options.include_rejected_activity {
+        let rejected = filter
+            .filter_actions(state.rejected)
+            .into_iter()
+            .map(|h| (h.action_seq(), h.into_hash()))
+            .collect();
+        ChainItems::Hashes(rejected)
+    } else {
+        ChainItems::NotRequested
+    };
+
+    Ok(GetAgentActivityResult {
+        response: AgentActivityResponse {
+            agent,
+            valid_activity,
+            rejected_activity,
+            status,
+            highest_observed,
+        },
+        status_authoritative,
+    })
+}
+
+/// Given a page returned by a range-bounded [`GetAgentActivityQuery`], returns
+/// the `action_seq` to pass as [`ActivityWindow::start_seq`] to fetch the
+/// next page: one past the highest seq among the page's valid and rejected
+/// hashes. Returns `None` for a page with no such hashes, or once that next
+/// seq would pass `end_seq`. With no `end_seq` the chain tip is not
+/// detected: a page that ends at the tip still yields `Some`, and the page
+/// fetched from it comes back empty.
+pub fn next_start_seq(result: &GetAgentActivityResult, window: &ActivityWindow) -> Option<u32> {
+    fn last_seq(items: &ChainItems<ActionHash>) -> Option<u32> {
+        match items {
+            ChainItems::Hashes(hashes) => hashes.last().map(|(seq, _)| *seq),
+            _ => None,
+        }
+    }
+    let last = std::cmp::max(
+        last_seq(&result.response.valid_activity),
+        last_seq(&result.response.rejected_activity),
+    )?;
+    let next = last.checked_add(1)?;
+    match window.end_seq {
+        Some(end) if next > end => None,
+        _ => Some(next),
+    }
+}
+
+/// Computes an author's true [`ChainStatus`] and [`HighestObserved`] without
+/// paying for a range bound. Unlike [`GetAgentActivityQuery`] this never
+/// deserializes an action blob: it only pulls `seq`, `hash`,
+/// `validation_status` and `when_integrated`, so it stays cheap to run over the whole chain even
+/// when the caller only wants a single bounded page from the main query.
+#[derive(Debug, Clone)]
+pub struct ChainHeadQuery {
+    agent: AgentPubKey,
+}
+
+impl ChainHeadQuery {
+    pub fn new(agent: AgentPubKey) -> Self {
+        Self { agent }
+    }
+}
+
+#[derive(Debug)]
+pub struct HeadItem {
+    seq: u32,
+    hash: ActionHash,
+}
+
+#[derive(Debug, Default)]
+pub struct HeadState {
+    valid: Vec<HeadItem>,
+    rejected: Vec<HeadItem>,
+    pending: Vec<HeadItem>,
+    status: Option<ChainStatus>,
+}
+
+impl Query for ChainHeadQuery {
+    type Item = Judged<(HeadItem, bool)>;
+    type State = HeadState;
+    type Output = (ChainStatus, Option<HighestObserved>);
+
+    fn query(&self) -> String {
+        "
+            SELECT Action.seq AS action_seq, Action.hash, DhtOp.validation_status,
+            DhtOp.when_integrated
+            FROM Action
+            JOIN DhtOp ON DhtOp.action_hash = Action.hash
+            WHERE Action.author = :author
+            AND DhtOp.type = :op_type
+            ORDER BY Action.seq ASC
+        "
+        .to_string()
+    }
+
+    fn params(&self) -> Vec<holochain_state::query::Params> {
+        (named_params! {
+            ":author": self.agent,
+            ":op_type": ChainOpType::RegisterAgentActivity,
+        })
+        .to_vec()
+    }
+
+    fn init_fold(&self) -> StateQueryResult<Self::State> {
+        Ok(Default::default())
+    }
+
+    fn as_filter(&self) -> Box<dyn Fn(&QueryData<Self>) -> bool> {
+        unimplemented!("This query should not be used with the scratch")
+    }
+
+    fn as_map(&self) -> Arc<dyn Fn(&Row) -> StateQueryResult<Self::Item>> {
+        Arc::new(move |row| {
+            let validation_status: Option<ValidationStatus> = row.get("validation_status")?;
+            let seq: u32 = row.get("action_seq")?;
+            let hash: ActionHash = row.get("hash")?;
+            let integrated: Option<Timestamp> = row.get("when_integrated")?;
+            Ok(Judged::raw((HeadItem { seq, hash }, integrated.is_some()), validation_status))
+        })
+    }
+
     fn fold(&self, mut state: Self::State, item: Self::Item) -> StateQueryResult<Self::State> {
         let status = item.validation_status();
-        match (status, item.data) {
-            (Some(ValidationStatus::Valid), Item::Integrated(action)) => {
-                let seq = action.action_seq();
+        let (head, integrated) = item.data;
+        match (status, integrated) {
+            (Some(ValidationStatus::Valid), true) => {
                 if state.status.is_none() {
-                    let fork = state.valid.last().and_then(|v| {
-                        if seq == v.action_seq() {
-                            Some(v)
-                        } else {
-                            None
-                        }
-                    });
-                    if let Some(fork) = fork {
-                        state.status = Some(ChainStatus::Forked(ChainFork {
-                            fork_seq: seq,
-                            first_action: action.get_hash().clone(),
-                            second_action: fork.get_hash().clone(),
-                        }));
+                    if let Some(fork) = detect_fork(state.valid.last(), &head) {
+                        state.status = Some(fork);
                     }
                 }
-
-                state.valid.push(action);
+                state.valid.push(head);
             }
-            (Some(ValidationStatus::Rejected), Item::Integrated(action)) => {
+            (Some(ValidationStatus::Rejected), true) => {
                 if state.status.is_none() {
                     state.status = Some(ChainStatus::Invalid(ChainHead {
-                        action_seq: action.action_seq(),
-                        hash: action.get_hash().clone(),
+                        action_seq: head.seq,
+                        hash: head.hash.clone(),
                     }));
                 }
-                state.rejected.push(action);
+                state.rejected.push(head);
             }
-            (_, Item::Pending(data)) => state.pending.push(data),
+            (_, false) => state.pending.push(head),
             _ => (),
         }
         Ok(state)
@@ -130,91 +495,78 @@ impl Query for GetAgentActivityQuery {
     where
         S: Store,
     {
-        let highest_observed = compute_highest_observed(&state);
-        let status = compute_chain_status(&state);
-
-        let valid = state.valid;
-        let rejected = state.rejected;
-        let valid_activity = if self.options.include_valid_activity {
-            let valid = self
-                .filter
-                .filter_actions(valid)
-                .into_iter()
-                .map(|h| (h.action_seq(), h.into_hash()))
-                .collect();
-            ChainItems::Hashes(valid)
-        } else {
-            ChainItems::NotRequested
-        };
-        let rejected_activity = if self.options.include_rejected_activity {
-            let rejected = self
-                .filter
-                .filter_actions(rejected)
-                .into_iter()
-                .map(|h| (h.action_seq(), h.into_hash()))
-                .collect();
-            ChainItems::Hashes(rejected)
-        } else {
-            ChainItems::NotRequested
-        };
-
-        Ok(AgentActivityResponse {
-            agent: self.agent.clone(),
-            valid_activity,
-            rejected_activity,
-            status,
-            highest_observed,
-        })
+        let status = chain_status(state.status.clone(), &state.valid, &state.rejected);
+        let highest_observed = highest_observed(
+            state.valid.last(),
+            state.rejected.last(),
+            state.pending.last(),
+        );
+        Ok((status, highest_observed))
     }
 }
 
-fn compute_chain_status(state: &State) -> ChainStatus {
-    state.status.clone().unwrap_or_else(|| {
-        if state.valid.is_empty() && state.rejected.is_empty() {
-            ChainStatus::Empty
-        } else {
-            let last = state.valid.last().expect("Safe due to is_empty check");
-            ChainStatus::Valid(ChainHead {
-                action_seq: last.action_seq(),
-                hash: last.get_hash().clone(),
-            })
-        }
-    })
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn compute_highest_observed(state: &State) -> Option<HighestObserved> {
-    let mut highest_observed = None;
-    let mut hashes = Vec::new();
-    let mut check_highest = |seq: u32, hash: &ActionHash| {
-        if highest_observed.is_none() {
-            highest_observed = Some(seq);
-            hashes.push(hash.clone());
-        } else {
-            let last = highest_observed
-                .as_mut()
-                .expect("Safe due to none check above");
-            match seq.cmp(last) {
-                std::cmp::Ordering::Less => {}
-                std::cmp::Ordering::Equal => hashes.push(hash.clone()),
-                std::cmp::Ordering::Greater => {
-                    hashes.clear();
-                    hashes.push(hash.clone());
-                    *last = seq;
-                }
-            }
+    fn result_with(
+        valid: Vec<(u32, ActionHash)>,
+        rejected: Vec<(u32, ActionHash)>,
+    ) -> GetAgentActivityResult {
+        GetAgentActivityResult {
+            response: AgentActivityResponse {
+                agent: fixt::fixt!(AgentPubKey),
+                valid_activity: ChainItems::Hashes(valid),
+                rejected_activity: ChainItems::Hashes(rejected),
+                status: ChainStatus::Empty,
+                highest_observed: None,
+            },
+            status_authoritative: true,
         }
-    };
-    if let Some(valid) = state.valid.last() {
-        check_highest(valid.action_seq(), valid.get_hash());
     }
-    if let Some(rejected) = state.rejected.last() {
-        check_highest(rejected.action_seq(), rejected.get_hash());
+
+    #[test]
+    fn next_start_seq_advances_past_the_last_seen_seq() {
+        let hash = fixt::fixt!(ActionHash);
+        let result = result_with(vec![(0, hash.clone()), (1, hash)], vec![]);
+        let window = ActivityWindow::default();
+        assert_eq!(next_start_seq(&result, &window), Some(2));
+    }
+
+    #[test]
+    fn next_start_seq_takes_the_max_of_valid_and_rejected() {
+        let hash = fixt::fixt!(ActionHash);
+        let result = result_with(vec![(0, hash.clone())], vec![(5, hash)]);
+        let window = ActivityWindow::default();
+        assert_eq!(next_start_seq(&result, &window), Some(6));
     }
-    if let Some(pending) = state.pending.last() {
-        check_highest(pending.action_seq(), pending.get_hash());
+
+    #[test]
+    fn next_start_seq_stops_once_end_seq_is_reached() {
+        let hash = fixt::fixt!(ActionHash);
+        let result = result_with(vec![(0, hash.clone()), (3, hash)], vec![]);
+        let window = ActivityWindow {
+            end_seq: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(next_start_seq(&result, &window), None);
+    }
+
+    #[test]
+    fn next_start_seq_continues_short_of_end_seq() {
+        let hash = fixt::fixt!(ActionHash);
+        let result = result_with(vec![(0, hash.clone()), (1, hash)], vec![]);
+        let window = ActivityWindow {
+            end_seq: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(next_start_seq(&result, &window), Some(2));
+    }
+
+    #[test]
+    fn next_start_seq_is_none_for_an_empty_page() {
+        let result = result_with(vec![], vec![]);
+        let window = ActivityWindow::default();
+        assert_eq!(next_start_seq(&result, &window), None);
     }
-    highest_observed.map(|action_seq| HighestObserved {
-        action_seq,
-        hash: hashes,
-    })
 }
@@ -0,0 +1,149 @@
+//! Authority-side `get_agent_activity` query execution.
+//!
+//! This is the actual call site the conductor's `get_agent_activity`
+//! request handling dispatches to: it runs [`GetAgentActivityQuery`]
+//! (through the shared [`AgentActivityCache`]) against the DHT database, and
+//! — since a bounded page's own `status`/`highest_observed` aren't always
+//! authoritative for the whole chain — falls back to the cheap
+//! [`ChainHeadQuery`] to fill in the true chain head whenever they aren't.
+
+pub mod batch;
+pub mod cache;
+pub mod hashes;
+pub mod subscribe;
+
+use batch::{GetAgentActivityBatchQuery, MAX_BATCH_AGENTS};
+use cache::AgentActivityCache;
+use hashes::{ActivityWindow, ChainHeadQuery, GetAgentActivityQuery, GetAgentActivityResult};
+use holo_hash::AgentPubKey;
+use holochain_p2p::event::GetActivityOptions;
+use holochain_sqlite::db::DbKindDht;
+use holochain_state::prelude::*;
+use subscribe::{ActivityEvent, ActivitySubscriptionRequest, ActivitySubscriptions};
+use tokio::sync::mpsc;
+
+/// Runs [`GetAgentActivityQuery`] for `agent`/`filter`/`options`/`window`,
+/// backed by `cache`. If the result's `status`/`highest_observed` aren't
+/// authoritative for the whole chain (the window was bounded by
+/// `start_seq`/`end_seq`/`limit`), runs [`ChainHeadQuery`] as well and splices its
+/// (always-authoritative) status/highest_observed into the response before
+/// returning it, so callers never have to reason about
+/// `status_authoritative` themselves.
+pub async fn handle_get_agent_activity(
+    db: &DbRead<DbKindDht>,
+    cache: &AgentActivityCache,
+    agent: AgentPubKey,
+    filter: ChainQueryFilter,
+    options: GetActivityOptions,
+    window: ActivityWindow,
+) -> StateQueryResult<AgentActivityResponse<ActionHash>> {
+    let result = db
+        .read_async({
+            let agent = agent.clone();
+            let filter = filter.clone();
+            move |txn| {
+                cache.get_or_try_compute(&agent, &filter, &options, &window, || {
+                    GetAgentActivityQuery::new(agent.clone(), filter.clone(), options.clone(), window)
+                        .run(Txn::from(&txn))
+                })
+            }
+        })
+        .await?;
+
+    if result.status_authoritative {
+        return Ok(result.response);
+    }
+
+    let (status, highest_observed) = db
+        .read_async(move |txn| ChainHeadQuery::new(agent).run(Txn::from(&txn)))
+        .await?;
+
+    Ok(AgentActivityResponse {
+        status,
+        highest_observed,
+        ..result.response
+    })
+}
+
+/// Neighborhood/shard warm-up's entry point for
+/// [`GetAgentActivityBatchQuery`]: answers for every agent in `agents` with
+/// as few SQL round-trips as [`batch::MAX_BATCH_AGENTS`] allows, instead of
+/// one [`handle_get_agent_activity`] call per agent. `agents` is split into
+/// chunks of at most `MAX_BATCH_AGENTS` up front — one
+/// `GetAgentActivityBatchQuery` per chunk, each its own `db.read_async` — and
+/// the per-chunk results are concatenated back into one `Vec` in the same
+/// order callers would see from querying each agent individually (an agent
+/// repeated within a chunk is answered once, at its first position). Bypasses
+/// [`AgentActivityCache`] entirely: warm-up is a bulk cold read over many
+/// agents at once, not the repeated single-agent lookup the cache is for.
+pub async fn handle_get_agent_activity_batch(
+    db: &DbRead<DbKindDht>,
+    agents: Vec<AgentPubKey>,
+    filter: ChainQueryFilter,
+    options: GetActivityOptions,
+) -> StateQueryResult<Vec<GetAgentActivityResult>> {
+    let mut results = Vec::with_capacity(agents.len());
+    for chunk in agents.chunks(MAX_BATCH_AGENTS) {
+        let chunk = chunk.to_vec();
+        let filter = filter.clone();
+        let options = options.clone();
+        let chunk_results = db
+            .read_async(move |txn| {
+                GetAgentActivityBatchQuery::new(chunk, filter, options).run(Txn::from(&txn))
+            })
+            .await?;
+        results.extend(chunk_results);
+    }
+    Ok(results)
+}
+
+/// The client-reachable entry point for [`ActivitySubscriptions::subscribe`]:
+/// opens the channel, then — inside the same DB read closure that registers
+/// the subscription — runs [`GetAgentActivityQuery`] to
+/// replay the chain as it now stands. Running the replay query inside the
+/// same synchronous closure `subscribe` calls *after* registering is what
+/// keeps the register-before-replay guarantee intact even though opening a
+/// DB connection at all is async: registration and the replay read both
+/// happen before this function's `.await` ever yields back to the caller.
+pub async fn handle_subscribe_agent_activity(
+    db: &DbRead<DbKindDht>,
+    subscriptions: &ActivitySubscriptions,
+    request: ActivitySubscriptionRequest,
+    buffer: usize,
+) -> StateQueryResult<mpsc::Receiver<ActivityEvent>> {
+    let agent = request.agent.clone();
+    let filter = request.filter.clone();
+    let options = request.options.clone();
+    db.read_async(move |txn| {
+        subscriptions.subscribe(
+            request,
+            || {
+                GetAgentActivityQuery::new(agent, filter, options, ActivityWindow::default())
+                    .run(Txn::from(&txn))
+                    .map(|result| result.response)
+            },
+            buffer,
+        )
+    })
+    .await
+}
+
+/// The DhtOp integration workflow's commit-point hook for this module: call
+/// this for `author` immediately after their `RegisterAgentActivity` op is
+/// committed and visible to readers, so [`AgentActivityCache`] never serves
+/// a chain head that predates it and every open [`ActivitySubscriptions`]
+/// subscriber sees the new action. (The integration workflow loop itself
+/// lives in the conductor crate, outside this one — this is the function it
+/// needs to call, not a replacement for wiring that call up.)
+pub fn handle_register_agent_activity_integrated(
+    cache: &AgentActivityCache,
+    subscriptions: &ActivitySubscriptions,
+    author: &AgentPubKey,
+    action: ActionHashed,
+    validation_status: Option<ValidationStatus>,
+    status: &ChainStatus,
+    highest_observed: &Option<HighestObserved>,
+) {
+    cache.invalidate(author);
+    subscriptions.notify_integrated(author, action, validation_status, status, highest_observed);
+}
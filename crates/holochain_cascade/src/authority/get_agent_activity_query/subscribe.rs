@@ -0,0 +1,207 @@
+//! Live subscriptions over an agent's activity.
+//!
+//! A client registers a [`ChainQueryFilter`] once via
+//! [`ActivitySubscriptions::subscribe`] and gets back a channel that first
+//! replays the chain as it currently stands, then emits an
+//! [`ActivityEvent`] each time the integration workflow commits a matching
+//! `RegisterAgentActivity` op, instead of having to re-run
+//! [`GetAgentActivityQuery`](super::hashes::GetAgentActivityQuery) on a poll
+//! loop.
+
+use holo_hash::{ActionHash, AgentPubKey};
+use holochain_p2p::event::GetActivityOptions;
+use holochain_state::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// The `{ agent, filter, options }` triple a client sends to open a
+/// subscription. Mirrors the arguments to `GetAgentActivityQuery::new` so
+/// the initial replay and the live stream are governed by the same filter.
+pub struct ActivitySubscriptionRequest {
+    pub agent: AgentPubKey,
+    pub filter: ChainQueryFilter,
+    pub options: GetActivityOptions,
+}
+
+/// One delivered action, carrying the chain-status context a subscriber
+/// needs to track forks and invalid heads incrementally instead of
+/// re-querying the whole chain.
+#[derive(Debug, Clone)]
+pub struct ActivityEvent {
+    pub action_seq: u32,
+    pub hash: ActionHash,
+    pub validation_status: Option<ValidationStatus>,
+    pub status: ChainStatus,
+    pub highest_observed: Option<HighestObserved>,
+}
+
+struct Subscriber {
+    filter: ChainQueryFilter,
+    options: GetActivityOptions,
+    sender: mpsc::Sender<ActivityEvent>,
+}
+
+/// Registry of open subscriptions, grouped by the author whose chain they
+/// follow. The integration workflow calls [`Self::notify_integrated`] after
+/// committing a `RegisterAgentActivity` op so it can fan the new action out
+/// to every subscriber of that author.
+#[derive(Default)]
+pub struct ActivitySubscriptions {
+    by_agent: Mutex<HashMap<AgentPubKey, Vec<Subscriber>>>,
+}
+
+impl ActivitySubscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `request`, then runs `current` (a `GetAgentActivityQuery`
+    /// for `request`, run by the caller) to replay the chain as it now
+    /// stands onto the returned receiver, and keeps the subscription open
+    /// for subsequent [`Self::notify_integrated`] calls.
+    ///
+    /// Registration happens *before* `current` runs, not after, so there is
+    /// no gap in which an action integrated between the snapshot and
+    /// registration would be neither in the replay nor delivered live: once
+    /// registered, every later `notify_integrated` call is seen, even one
+    /// that races with `current` still running. The cost is that an action
+    /// integrated during that race may be delivered twice (once in the
+    /// replay, once live) — duplicates are safe to de-duplicate on
+    /// `action_seq`/`hash`, but a silently dropped action is not.
+    pub fn subscribe(
+        &self,
+        request: ActivitySubscriptionRequest,
+        current: impl FnOnce() -> StateQueryResult<AgentActivityResponse<ActionHash>>,
+        buffer: usize,
+    ) -> StateQueryResult<mpsc::Receiver<ActivityEvent>> {
+        let (sender, receiver) = mpsc::channel(buffer.max(1));
+
+        self.by_agent
+            .lock()
+            .unwrap()
+            .entry(request.agent)
+            .or_default()
+            .push(Subscriber {
+                filter: request.filter,
+                options: request.options,
+                sender: sender.clone(),
+            });
+
+        let current = current()?;
+        let replay = |items: ChainItems<ActionHash>, validation_status: ValidationStatus| {
+            let hashes = match items {
+                ChainItems::Hashes(hashes) => hashes,
+                _ => Vec::new(),
+            };
+            for (action_seq, hash) in hashes {
+                let _ = sender.try_send(ActivityEvent {
+                    action_seq,
+                    hash,
+                    validation_status: Some(validation_status),
+                    status: current.status.clone(),
+                    highest_observed: current.highest_observed.clone(),
+                });
+            }
+        };
+        replay(current.valid_activity, ValidationStatus::Valid);
+        replay(current.rejected_activity, ValidationStatus::Rejected);
+
+        Ok(receiver)
+    }
+
+    /// Fans a newly integrated `RegisterAgentActivity` action out to every
+    /// subscriber of `agent` whose `include_valid_activity`/
+    /// `include_rejected_activity` options and filter admit it. Drops
+    /// subscribers whose receiver has been closed.
+    pub fn notify_integrated(
+        &self,
+        agent: &AgentPubKey,
+        action: ActionHashed,
+        validation_status: Option<ValidationStatus>,
+        status: &ChainStatus,
+        highest_observed: &Option<HighestObserved>,
+    ) {
+        let mut by_agent = self.by_agent.lock().unwrap();
+        let Some(subscribers) = by_agent.get_mut(agent) else {
+            return;
+        };
+        subscribers.retain(|subscriber| {
+            if subscriber.sender.is_closed() {
+                return false;
+            }
+            let included = match validation_status {
+                Some(ValidationStatus::Valid) => subscriber.options.include_valid_activity,
+                Some(ValidationStatus::Rejected) => subscriber.options.include_rejected_activity,
+                _ => false,
+            };
+            if included && !subscriber.filter.clone().filter_actions(vec![action.clone()]).is_empty() {
+                let event = ActivityEvent {
+                    action_seq: action.action_seq(),
+                    hash: action.get_hash().clone(),
+                    validation_status,
+                    status: status.clone(),
+                    highest_observed: highest_observed.clone(),
+                };
+                let _ = subscriber.sender.try_send(event);
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribe_replays_then_notify_integrated_delivers_live_events() {
+        let subscriptions = ActivitySubscriptions::new();
+        let agent = fixt::fixt!(AgentPubKey);
+        let replayed_hash = fixt::fixt!(ActionHash);
+        let request = ActivitySubscriptionRequest {
+            agent: agent.clone(),
+            filter: ChainQueryFilter::new(),
+            options: GetActivityOptions {
+                include_valid_activity: true,
+                include_rejected_activity: true,
+                ..Default::default()
+            },
+        };
+
+        let mut receiver = subscriptions
+            .subscribe(
+                request,
+                || {
+                    Ok(AgentActivityResponse {
+                        agent: agent.clone(),
+                        valid_activity: ChainItems::Hashes(vec![(0, replayed_hash.clone())]),
+                        rejected_activity: ChainItems::NotRequested,
+                        status: ChainStatus::Empty,
+                        highest_observed: None,
+                    })
+                },
+                8,
+            )
+            .unwrap();
+
+        let replayed = receiver.try_recv().expect("the replay should deliver the current chain");
+        assert_eq!(replayed.action_seq, 0);
+        assert_eq!(replayed.hash, replayed_hash);
+
+        let live_action = ActionHashed::with_pre_hashed(fixt::fixt!(Action), fixt::fixt!(ActionHash));
+        let live_hash = live_action.get_hash().clone();
+        subscriptions.notify_integrated(
+            &agent,
+            live_action,
+            Some(ValidationStatus::Valid),
+            &ChainStatus::Empty,
+            &None,
+        );
+
+        let live = receiver
+            .try_recv()
+            .expect("notify_integrated should deliver to the already-registered subscriber");
+        assert_eq!(live.hash, live_hash);
+    }
+}